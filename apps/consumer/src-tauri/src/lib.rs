@@ -7,8 +7,14 @@
 
 pub mod oauth;
 
-use oauth::{OAuthClient, OAuthProvider, TokenData, is_token_expired};
+use oauth::provider::{Provider, ProviderRegistry};
+use oauth::scoped_keys::{ScopedKey, ScopedKeysSession};
+use oauth::{
+    token_store, DeviceFlow, IntrospectionInfo, OAuthClient, OAuthProvider, RefreshError,
+    TokenData, TokenTypeHint, is_token_expired, OAUTH_ERROR_PENDING_SECS, OAUTH_MIN_TIME_LEFT,
+};
 use oauth2::PkceCodeVerifier;
+use std::collections::HashMap;
 use std::sync::Mutex;
 use std::time::Duration;
 use tauri::{Manager, Emitter, State};
@@ -41,6 +47,47 @@ struct AppState {
     microsoft_csrf_state: Mutex<Option<String>>,
     /// CSRF state for Google OAuth
     google_csrf_state: Mutex<Option<String>>,
+    /// Cached access tokens keyed by the set of requested scopes
+    access_token_cache: Mutex<AccessTokenCache>,
+    /// Per-provider timestamp (ms) until which refresh attempts short-circuit
+    refresh_error_pending: Mutex<HashMap<String, i64>>,
+    /// Ephemeral ECDH session for the in-flight scoped-keys authorization
+    scoped_keys_session: Mutex<Option<ScopedKeysSession>>,
+    /// Registry of OAuth providers, seeded with the built-in first-party set
+    provider_registry: Mutex<ProviderRegistry>,
+}
+
+/// In-memory access-token cache keyed by the sorted set of requested scopes
+///
+/// Lets callers ask for a valid token by scope without threading `TokenData`
+/// through every command: a cached token with more than `OAUTH_MIN_TIME_LEFT`
+/// seconds remaining is returned directly, otherwise it is refreshed.
+#[derive(Default)]
+struct AccessTokenCache {
+    tokens: HashMap<String, TokenData>,
+}
+
+impl AccessTokenCache {
+    /// Build a stable cache key from a scope set regardless of ordering.
+    fn key(provider: OAuthProvider, scopes: &[String]) -> String {
+        let mut scopes = scopes.to_vec();
+        scopes.sort();
+        format!("{:?}:{}", provider, scopes.join(" "))
+    }
+
+    fn get(&self, key: &str) -> Option<&TokenData> {
+        self.tokens.get(key)
+    }
+
+    fn put(&mut self, key: String, token: TokenData) {
+        self.tokens.insert(key, token);
+    }
+}
+
+/// True when a cached token has more than the minimum lifetime left.
+fn has_min_time_left(token: &TokenData) -> bool {
+    let now = chrono::Utc::now().timestamp_millis();
+    token.expires_at - now > OAUTH_MIN_TIME_LEFT * 1000
 }
 
 /// Initialize OAuth flow and return authorization URL
@@ -160,16 +207,39 @@ async fn refresh_access_token(
     client_id: String,
     redirect_uri: String,
     refresh_token: String,
+    state: State<'_, AppState>,
 ) -> Result<TokenData, String> {
-    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
-        .map_err(|e| e.to_string())?;
+    let key = format!("{:?}", provider);
+    let now = chrono::Utc::now().timestamp_millis();
+
+    // Short-circuit while a recent hard failure keeps this provider in cooldown.
+    {
+        let pending = state.refresh_error_pending.lock().unwrap();
+        if let Some(&until) = pending.get(&key) {
+            if now < until {
+                return Err("refresh error-pending: retry later".to_string());
+            }
+        }
+    }
 
-    let new_token = oauth_client
-        .refresh_token(refresh_token)
-        .await
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
         .map_err(|e| e.to_string())?;
 
-    Ok(new_token)
+    match oauth_client.refresh_token_resilient(refresh_token).await {
+        Ok(new_token) => {
+            state.refresh_error_pending.lock().unwrap().remove(&key);
+            Ok(new_token)
+        }
+        Err(e) => {
+            // Record a cooldown window so repeated attempts don't hammer the endpoint.
+            let until = now + OAUTH_ERROR_PENDING_SECS * 1000;
+            state.refresh_error_pending.lock().unwrap().insert(key, until);
+            match e {
+                RefreshError::ReauthNeeded(msg) => Err(format!("reauth_needed: {}", msg)),
+                RefreshError::Transient(msg) => Err(format!("transient: {}", msg)),
+            }
+        }
+    }
 }
 
 /// Check if token is expired
@@ -178,7 +248,297 @@ fn check_token_expiration(token_data: TokenData) -> Result<bool, String> {
     Ok(is_token_expired(&token_data))
 }
 
-use std::collections::HashMap;
+/// Return a valid access token for a scope set, refreshing if needed
+///
+/// Checks the in-memory `AccessTokenCache` first; a cached token with more than
+/// `OAUTH_MIN_TIME_LEFT` seconds left is returned directly. Otherwise the stored
+/// refresh token is used to mint a fresh token and the cache is repopulated.
+#[tauri::command]
+async fn get_cached_access_token(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    account: String,
+    scopes: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let key = AccessTokenCache::key(provider, &scopes);
+
+    {
+        let cache = state.access_token_cache.lock().unwrap();
+        if let Some(token) = cache.get(&key) {
+            if has_min_time_left(token) {
+                return Ok(token.access_token.clone());
+            }
+        }
+    }
+
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    let refreshed = oauth_client
+        .refresh_stored_token(&account)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let access_token = refreshed.access_token.clone();
+    state
+        .access_token_cache
+        .lock()
+        .unwrap()
+        .put(key, refreshed);
+
+    Ok(access_token)
+}
+
+/// Register an IdP at runtime from its OIDC discovery document
+///
+/// Fetches `{issuer}/.well-known/openid-configuration`, builds a provider
+/// descriptor, and adds it to the registry under `name`.
+#[tauri::command]
+async fn register_provider(
+    name: String,
+    issuer: String,
+    default_scopes: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<Provider, String> {
+    let descriptor = Provider::discover(name, &issuer, default_scopes)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    state
+        .provider_registry
+        .lock()
+        .unwrap()
+        .register(descriptor.clone());
+
+    Ok(descriptor)
+}
+
+/// Obtain a token via the client-credentials grant for a registered provider
+#[tauri::command]
+async fn client_credentials_token(
+    provider_name: String,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<TokenData, String> {
+    let descriptor = {
+        let registry = state.provider_registry.lock().unwrap();
+        registry
+            .get(&provider_name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown provider: {}", provider_name))?
+    };
+
+    // Cache client-credentials tokens by provider name and scope set, like user tokens.
+    let mut sorted_scopes = scopes.clone();
+    sorted_scopes.sort();
+    let key = format!("client_credentials:{}:{}", provider_name, sorted_scopes.join(" "));
+    {
+        let cache = state.access_token_cache.lock().unwrap();
+        if let Some(token) = cache.get(&key) {
+            if has_min_time_left(token) {
+                return Ok(token.clone());
+            }
+        }
+    }
+
+    let oauth_client =
+        OAuthClient::from_descriptor(&descriptor, client_id, Some(client_secret), redirect_uri)
+            .map_err(|e| e.to_string())?;
+
+    let token = oauth_client
+        .client_credentials(scopes)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    state.access_token_cache.lock().unwrap().put(key, token.clone());
+    Ok(token)
+}
+
+/// Start an OAuth flow that also requests scoped encryption keys
+///
+/// Generates an ephemeral ECDH key pair, stores it for the callback, and
+/// returns an authorization URL carrying the `keys_jwk` parameter.
+#[tauri::command]
+async fn start_oauth_with_keys(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    key_scope: String,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    let (auth_url, pkce_verifier, csrf_token, session) = oauth_client
+        .get_authorization_url_with_scoped_keys(scopes, key_scope)
+        .map_err(|e| e.to_string())?;
+
+    match provider {
+        OAuthProvider::Microsoft => {
+            *state.microsoft_pkce_verifier.lock().unwrap() = Some(pkce_verifier);
+            *state.microsoft_csrf_state.lock().unwrap() = Some(csrf_token.secret().clone());
+        }
+        OAuthProvider::Google => {
+            *state.google_pkce_verifier.lock().unwrap() = Some(pkce_verifier);
+            *state.google_csrf_state.lock().unwrap() = Some(csrf_token.secret().clone());
+        }
+    }
+    *state.scoped_keys_session.lock().unwrap() = Some(session);
+
+    Ok(auth_url)
+}
+
+/// Unwrap per-scope encryption keys from a provider `keys_jwt`
+///
+/// Returns `None` for providers that don't implement the scoped-keys extension.
+#[tauri::command]
+fn get_scoped_keys(
+    keys_jwt: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Option<Vec<ScopedKey>>, String> {
+    let session = state.scoped_keys_session.lock().unwrap().take();
+    let session = match session {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+
+    session
+        .unwrap_keys(keys_jwt.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+/// Encrypt a mail/profile blob at rest with a scoped key (AES-256-GCM)
+///
+/// The frontend calls this before writing fetched mail/profile data into its
+/// local cache so nothing sensitive is persisted in the clear; the returned
+/// string is fed back to [`decrypt_cached_blob`] on read.
+#[tauri::command]
+fn encrypt_cached_blob(key: ScopedKey, plaintext: Vec<u8>) -> Result<String, String> {
+    oauth::scoped_keys::encrypt_blob(&key, &plaintext).map_err(|e| e.to_string())
+}
+
+/// Decrypt a blob previously produced by [`encrypt_cached_blob`].
+#[tauri::command]
+fn decrypt_cached_blob(key: ScopedKey, blob: String) -> Result<Vec<u8>, String> {
+    oauth::scoped_keys::decrypt_blob(&key, &blob).map_err(|e| e.to_string())
+}
+
+/// Introspect an access token server-side (RFC 7662)
+#[tauri::command]
+async fn introspect_token(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    token: String,
+) -> Result<IntrospectionInfo, String> {
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    oauth_client
+        .introspect_token(token)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Revoke both access and refresh tokens server-side and clear stored copies
+#[tauri::command]
+async fn revoke_tokens(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    account: String,
+    tokens: TokenData,
+) -> Result<(), String> {
+    log::info!("[OAuth] revoke_tokens called for {:?}", provider);
+
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    oauth_client
+        .revoke_token(tokens.access_token, TokenTypeHint::AccessToken)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Some(refresh_token) = tokens.refresh_token {
+        oauth_client
+            .revoke_token(refresh_token, TokenTypeHint::RefreshToken)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    token_store::delete_tokens(provider, &account).map_err(|e| e.to_string())
+}
+
+/// Persist tokens for an account in the OS secure store
+#[tauri::command]
+fn save_tokens(
+    provider: OAuthProvider,
+    account: String,
+    tokens: TokenData,
+) -> Result<(), String> {
+    token_store::save_tokens(provider, &account, &tokens).map_err(|e| e.to_string())
+}
+
+/// Load tokens for an account from the OS secure store
+#[tauri::command]
+fn load_tokens(provider: OAuthProvider, account: String) -> Result<Option<TokenData>, String> {
+    token_store::load_tokens(provider, &account).map_err(|e| e.to_string())
+}
+
+/// Delete any stored tokens for an account
+#[tauri::command]
+fn delete_tokens(provider: OAuthProvider, account: String) -> Result<(), String> {
+    token_store::delete_tokens(provider, &account).map_err(|e| e.to_string())
+}
+
+/// Begin an OAuth 2.0 Device Authorization Grant (RFC 8628)
+///
+/// Returns the user code and verification URI for the frontend to display;
+/// `poll_device_oauth` then drives the token exchange in Rust.
+#[tauri::command]
+async fn start_device_oauth(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+) -> Result<DeviceFlow, String> {
+    log::info!("[OAuth] start_device_oauth called for {:?}", provider);
+
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    oauth_client
+        .start_device_flow(scopes)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Poll the device-flow token endpoint until the user approves or it expires
+#[tauri::command]
+async fn poll_device_oauth(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    device_code: String,
+    interval: u64,
+    expires_in: u64,
+) -> Result<TokenData, String> {
+    log::info!("[OAuth] poll_device_oauth called for {:?}", provider);
+
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    oauth_client
+        .poll_device_token(device_code, interval, expires_in)
+        .await
+        .map_err(|e| e.to_string())
+}
 
 /// Proxy HTTP request via Rust backend to bypass browser limits
 ///
@@ -242,6 +602,10 @@ pub fn run() {
             google_pkce_verifier: Mutex::new(None),
             microsoft_csrf_state: Mutex::new(None),
             google_csrf_state: Mutex::new(None),
+            access_token_cache: Mutex::new(AccessTokenCache::default()),
+            refresh_error_pending: Mutex::new(HashMap::new()),
+            scoped_keys_session: Mutex::new(None),
+            provider_registry: Mutex::new(ProviderRegistry::with_builtins()),
         })
         // IMPORTANT: Single instance plugin MUST be registered first
         .plugin(tauri_plugin_single_instance::init(|app, argv, _cwd| {
@@ -272,6 +636,20 @@ pub fn run() {
             complete_oauth,
             refresh_access_token,
             check_token_expiration,
+            save_tokens,
+            load_tokens,
+            delete_tokens,
+            revoke_tokens,
+            get_cached_access_token,
+            introspect_token,
+            start_oauth_with_keys,
+            get_scoped_keys,
+            encrypt_cached_blob,
+            decrypt_cached_blob,
+            register_provider,
+            client_credentials_token,
+            start_device_oauth,
+            poll_device_oauth,
             http_request
         ])
         // ALWAYS enable logging (both debug and release builds)
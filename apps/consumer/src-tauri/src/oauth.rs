@@ -7,20 +7,50 @@
 use serde::{Deserialize, Serialize};
 use oauth2::{
     AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
-    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl,
+    PkceCodeVerifier, RedirectUrl, RevocationUrl, Scope, StandardRevocableToken, TokenResponse,
+    TokenUrl,
 };
 use oauth2::basic::BasicClient;
 use oauth2::reqwest::async_http_client;
 use chrono::Utc;
 use std::error::Error;
 
+pub mod provider;
+pub mod scoped_keys;
+pub mod token_store;
+
+use scoped_keys::ScopedKeysSession;
+
 // Microsoft OAuth endpoints (using "consumers" for personal accounts)
 const MS_AUTH_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0/authorize";
 const MS_TOKEN_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0/token";
+const MS_DEVICE_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0/devicecode";
 
 // Google OAuth endpoints
 const GOOGLE_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const GOOGLE_DEVICE_URL: &str = "https://oauth2.googleapis.com/device/code";
+
+// RFC 7009 token revocation endpoints
+const MS_REVOCATION_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0/logout";
+const GOOGLE_REVOCATION_URL: &str = "https://oauth2.googleapis.com/revoke";
+
+// Token introspection endpoints (Google `tokeninfo`; Microsoft has none)
+const GOOGLE_TOKENINFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
+
+/// Minimum lifetime (seconds) a cached access token must have left before it is
+/// refreshed rather than handed back to a caller.
+pub const OAUTH_MIN_TIME_LEFT: i64 = 60;
+
+/// Total time budget (milliseconds) for retrying a transient refresh failure.
+pub const OAUTH_REFRESH_BUDGET_MS: u64 = 10_000;
+
+/// Per-attempt network timeout (milliseconds) for a single refresh request.
+pub const OAUTH_REFRESH_ATTEMPT_TIMEOUT_MS: u64 = 5_000;
+
+/// Cooldown (seconds) after a hard refresh failure during which further refresh
+/// attempts short-circuit instead of hammering the token endpoint.
+pub const OAUTH_ERROR_PENDING_SECS: i64 = 60;
 
 /// Provider type for OAuth
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
@@ -30,6 +60,14 @@ pub enum OAuthProvider {
     Google,
 }
 
+/// Hint for which token is being revoked (RFC 7009 `token_type_hint`)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenTypeHint {
+    AccessToken,
+    RefreshToken,
+}
+
 /// Token storage structure
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -41,9 +79,94 @@ pub struct TokenData {
     pub token_type: String,
 }
 
+/// Device authorization response returned by `start_device_flow`
+///
+/// Mirrors the RFC 8628 device authorization endpoint payload that the
+/// frontend renders while polling completes in Rust.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceFlow {
+    pub user_code: String,
+    pub verification_uri: String,
+    pub device_code: String,
+    pub interval: u64,
+    pub expires_in: u64,
+}
+
+/// Raw device authorization endpoint response (snake_case from the provider)
+#[derive(Debug, Deserialize)]
+struct DeviceAuthResponse {
+    device_code: String,
+    user_code: String,
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    #[serde(default = "default_interval")]
+    interval: u64,
+    expires_in: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+/// Raw token endpoint response used by the device-code polling loop
+#[derive(Debug, Deserialize)]
+struct DeviceTokenResponse {
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+    scope: Option<String>,
+    token_type: Option<String>,
+    error: Option<String>,
+}
+
+/// Classified refresh failure so the frontend can decide whether to re-login
+///
+/// `Transient` means the retry budget was exhausted on network/5xx errors and
+/// a later attempt may succeed; `ReauthNeeded` means the provider rejected the
+/// grant (e.g. `invalid_grant`) and the user must authenticate again.
+#[derive(Debug, Clone)]
+pub enum RefreshError {
+    Transient(String),
+    ReauthNeeded(String),
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshError::Transient(msg) => write!(f, "transient refresh error: {}", msg),
+            RefreshError::ReauthNeeded(msg) => write!(f, "reauthentication required: {}", msg),
+        }
+    }
+}
+
+impl Error for RefreshError {}
+
+/// Result of an RFC 7662 token introspection request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IntrospectionInfo {
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub scope: String,
+    #[serde(default)]
+    pub exp: Option<i64>,
+}
+
+/// Raw Google `tokeninfo` response fields we care about
+#[derive(Debug, Deserialize)]
+struct GoogleTokenInfo {
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
 /// OAuth client that works for both Microsoft and Google
 pub struct OAuthClient {
     provider: OAuthProvider,
+    client_id: String,
     oauth_client: BasicClient,
 }
 
@@ -60,6 +183,11 @@ impl OAuthClient {
             OAuthProvider::Google => (GOOGLE_AUTH_URL, GOOGLE_TOKEN_URL),
         };
 
+        let revocation_url = match provider {
+            OAuthProvider::Microsoft => MS_REVOCATION_URL,
+            OAuthProvider::Google => GOOGLE_REVOCATION_URL,
+        };
+
         let auth_url = AuthUrl::new(auth_url.to_string())?;
         let token_url = TokenUrl::new(token_url.to_string())?;
 
@@ -69,14 +197,130 @@ impl OAuthClient {
             auth_url,
             Some(token_url),
         )
-        .set_redirect_uri(RedirectUrl::new(redirect_uri)?);
+        .set_redirect_uri(RedirectUrl::new(redirect_uri)?)
+        .set_revocation_uri(RevocationUrl::new(revocation_url.to_string())?);
 
         Ok(Self {
             provider,
+            client_id,
             oauth_client,
         })
     }
 
+    /// Start an RFC 8628 device authorization grant
+    ///
+    /// POSTs the client id and requested scopes to the provider's device
+    /// authorization endpoint and returns the user code, verification URI and
+    /// the device code the caller polls with `poll_device_token`.
+    pub async fn start_device_flow(
+        &self,
+        scopes: Vec<String>,
+    ) -> Result<DeviceFlow, Box<dyn Error + Send + Sync>> {
+        let device_url = match self.provider {
+            OAuthProvider::Microsoft => MS_DEVICE_URL,
+            OAuthProvider::Google => GOOGLE_DEVICE_URL,
+        };
+
+        log::info!("[OAuth Rust] Starting device flow for {:?}", self.provider);
+
+        let scope = scopes.join(" ");
+        let params = [
+            ("client_id", self.client_id.as_str()),
+            ("scope", scope.as_str()),
+        ];
+
+        let response: DeviceAuthResponse = reqwest::Client::new()
+            .post(device_url)
+            .form(&params)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(DeviceFlow {
+            user_code: response.user_code,
+            verification_uri: response.verification_uri,
+            device_code: response.device_code,
+            interval: response.interval,
+            expires_in: response.expires_in,
+        })
+    }
+
+    /// Poll the token endpoint until the user approves the device flow
+    ///
+    /// Honors the RFC 8628 polling semantics: `authorization_pending` keeps
+    /// waiting, `slow_down` increases the interval by 5 seconds, and
+    /// `expired_token`/`access_denied` are surfaced as hard errors. Stops once
+    /// `expires_in` seconds have elapsed so a provider that never returns
+    /// `expired_token` can't keep us polling forever. On success returns the
+    /// same `TokenData` as `exchange_code`.
+    pub async fn poll_device_token(
+        &self,
+        device_code: String,
+        interval: u64,
+        expires_in: u64,
+    ) -> Result<TokenData, Box<dyn Error + Send + Sync>> {
+        let token_url = match self.provider {
+            OAuthProvider::Microsoft => MS_TOKEN_URL,
+            OAuthProvider::Google => GOOGLE_TOKEN_URL,
+        };
+
+        let mut interval = interval.max(1);
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(expires_in);
+        let client = reqwest::Client::new();
+
+        loop {
+            if tokio::time::Instant::now() >= deadline {
+                return Err("Device code expired".into());
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+            let params = [
+                ("client_id", self.client_id.as_str()),
+                ("device_code", device_code.as_str()),
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ];
+
+            let response: DeviceTokenResponse = client
+                .post(token_url)
+                .form(&params)
+                .send()
+                .await?
+                .json()
+                .await?;
+
+            if let Some(error) = response.error {
+                match error.as_str() {
+                    "authorization_pending" => continue,
+                    "slow_down" => {
+                        interval += 5;
+                        continue;
+                    }
+                    "expired_token" => return Err("Device code expired".into()),
+                    "access_denied" => return Err("Access denied by user".into()),
+                    other => return Err(format!("Device flow error: {}", other).into()),
+                }
+            }
+
+            let access_token = response
+                .access_token
+                .ok_or("No access token in device token response")?;
+            let expires_in_secs = response.expires_in.unwrap_or(3600);
+            let expires_at = Utc::now().timestamp_millis() + (expires_in_secs * 1000);
+
+            log::info!("[OAuth Rust] Device flow completed successfully");
+
+            return Ok(TokenData {
+                access_token,
+                refresh_token: response.refresh_token,
+                expires_at,
+                scope: response.scope.unwrap_or_default(),
+                token_type: response.token_type.unwrap_or_else(|| "Bearer".to_string()),
+            });
+        }
+    }
+
     /// Generate authorization URL with PKCE
     pub fn get_authorization_url(&self, scopes: Vec<String>) -> Result<(String, PkceCodeVerifier, CsrfToken), Box<dyn Error + Send + Sync>> {
         let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
@@ -101,6 +345,26 @@ impl OAuthClient {
         Ok((url_string, pkce_verifier, csrf_token))
     }
 
+    /// Generate an authorization URL that also requests scoped encryption keys
+    ///
+    /// Appends the ephemeral public JWK as `keys_jwk` and the key-bearing scope
+    /// so the provider returns a JWE-wrapped `keys_jwt` at code exchange. The
+    /// returned `ScopedKeysSession` must be retained to unwrap that reply.
+    pub fn get_authorization_url_with_scoped_keys(
+        &self,
+        mut scopes: Vec<String>,
+        key_scope: String,
+    ) -> Result<(String, PkceCodeVerifier, CsrfToken, ScopedKeysSession), Box<dyn Error + Send + Sync>>
+    {
+        let session = ScopedKeysSession::new();
+        scopes.push(key_scope);
+
+        let (mut url, verifier, csrf) = self.get_authorization_url(scopes)?;
+        url = format!("{}&keys_jwk={}", url, session.keys_jwk_param());
+
+        Ok((url, verifier, csrf, session))
+    }
+
     /// Exchange authorization code for tokens (happens in Rust, no CORS issues)
     pub async fn exchange_code(
         &self,
@@ -153,6 +417,80 @@ impl OAuthClient {
         })
     }
 
+    /// Build a client from a registered [`provider::Provider`] descriptor
+    ///
+    /// Used for runtime-registered IdPs; the built-in providers continue to use
+    /// [`OAuthClient::new`] so their call sites are untouched.
+    pub fn from_descriptor(
+        descriptor: &provider::Provider,
+        client_id: String,
+        client_secret: Option<String>,
+        redirect_uri: String,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut oauth_client = BasicClient::new(
+            ClientId::new(client_id.clone()),
+            client_secret.map(ClientSecret::new),
+            AuthUrl::new(descriptor.auth_url.clone())?,
+            Some(TokenUrl::new(descriptor.token_url.clone())?),
+        )
+        .set_redirect_uri(RedirectUrl::new(redirect_uri)?);
+
+        if let Some(url) = &descriptor.revocation_url {
+            oauth_client = oauth_client.set_revocation_uri(RevocationUrl::new(url.clone())?);
+        }
+
+        // Runtime providers default to Microsoft for provider-specific branching
+        // (e.g. Google's offline prompt) unless they match a built-in name.
+        let provider = if descriptor.name == "google" {
+            OAuthProvider::Google
+        } else {
+            OAuthProvider::Microsoft
+        };
+
+        Ok(Self {
+            provider,
+            client_id,
+            oauth_client,
+        })
+    }
+
+    /// Client-credentials grant for service-to-service calls without a user
+    ///
+    /// Caches its token the same way as user tokens via the caller's
+    /// `AccessTokenCache`.
+    pub async fn client_credentials(
+        &self,
+        scopes: Vec<String>,
+    ) -> Result<TokenData, Box<dyn Error + Send + Sync>> {
+        let mut request = self.oauth_client.exchange_client_credentials();
+        for scope in scopes {
+            request = request.add_scope(Scope::new(scope));
+        }
+
+        let token_result = request
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| format!("Client credentials grant failed: {:?}", e))?;
+
+        let expires_in_secs = token_result
+            .expires_in()
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(3600);
+        let expires_at = Utc::now().timestamp_millis() + (expires_in_secs * 1000);
+        let scope = token_result
+            .scopes()
+            .map(|scopes| scopes.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(" "))
+            .unwrap_or_default();
+
+        Ok(TokenData {
+            access_token: token_result.access_token().secret().to_string(),
+            refresh_token: None,
+            expires_at,
+            scope,
+            token_type: "Bearer".to_string(),
+        })
+    }
+
     /// Refresh access token using refresh token
     pub async fn refresh_token(&self, refresh_token: String) -> Result<TokenData, Box<dyn Error + Send + Sync>> {
         let refresh_token_clone = refresh_token.clone();
@@ -193,6 +531,159 @@ impl OAuthClient {
             token_type: "Bearer".to_string(),
         })
     }
+
+    /// Refresh with bounded retry/backoff, classifying terminal failures
+    ///
+    /// Retries network and 5xx failures with exponential backoff within
+    /// `OAUTH_REFRESH_BUDGET_MS`, but fails fast with `RefreshError::ReauthNeeded`
+    /// on `invalid_grant` so a revoked grant isn't retried pointlessly.
+    pub async fn refresh_token_resilient(
+        &self,
+        refresh_token: String,
+    ) -> Result<TokenData, RefreshError> {
+        let mut backoff_ms = 500u64;
+        let start = tokio::time::Instant::now();
+        let budget = std::time::Duration::from_millis(OAUTH_REFRESH_BUDGET_MS);
+        let attempt_timeout = std::time::Duration::from_millis(OAUTH_REFRESH_ATTEMPT_TIMEOUT_MS);
+
+        loop {
+            // Bound each attempt by the per-attempt timeout and by what is left of
+            // the overall budget, so a hung request can't block past it; a timeout
+            // is treated as a transient failure.
+            let remaining = budget.saturating_sub(start.elapsed());
+            let result = match tokio::time::timeout(
+                attempt_timeout.min(remaining),
+                self.refresh_token(refresh_token.clone()),
+            )
+            .await
+            {
+                Ok(result) => result,
+                Err(_) => Err("refresh request timed out".into()),
+            };
+
+            match result {
+                Ok(token) => return Ok(token),
+                Err(e) => {
+                    let message = e.to_string();
+                    // Terminal: the provider rejected the grant, retrying won't help.
+                    if message.contains("invalid_grant") {
+                        return Err(RefreshError::ReauthNeeded(message));
+                    }
+
+                    let backoff = std::time::Duration::from_millis(backoff_ms);
+                    if start.elapsed() + backoff >= budget {
+                        return Err(RefreshError::Transient(message));
+                    }
+
+                    log::warn!("[OAuth Rust] Transient refresh failure, backing off {}ms", backoff_ms);
+                    tokio::time::sleep(backoff).await;
+                    backoff_ms = (backoff_ms * 2).min(OAUTH_REFRESH_BUDGET_MS);
+                }
+            }
+        }
+    }
+
+    /// Introspect a token server-side
+    ///
+    /// The Microsoft identity platform exposes no RFC 7662 introspection
+    /// endpoint for v2.0 consumer tokens, so only Google is supported here via
+    /// its `tokeninfo` endpoint (a `GET` with the token in the query string).
+    /// An active token yields a JSON body with `scope`/`expires_in`; an invalid
+    /// one yields an `error`, which we map to `active = false`.
+    pub async fn introspect_token(
+        &self,
+        token: String,
+    ) -> Result<IntrospectionInfo, Box<dyn Error + Send + Sync>> {
+        match self.provider {
+            OAuthProvider::Google => {
+                let resp: GoogleTokenInfo = reqwest::Client::new()
+                    .get(GOOGLE_TOKENINFO_URL)
+                    .query(&[("access_token", token.as_str())])
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+
+                if resp.error.is_some() {
+                    return Ok(IntrospectionInfo {
+                        active: false,
+                        scope: String::new(),
+                        exp: None,
+                    });
+                }
+
+                Ok(IntrospectionInfo {
+                    active: true,
+                    scope: resp.scope.unwrap_or_default(),
+                    // `tokeninfo` reports the remaining lifetime in seconds
+                    // rather than an absolute `exp`; leave `exp` unset.
+                    exp: None,
+                })
+            }
+            OAuthProvider::Microsoft => {
+                Err("token introspection is not supported by the Microsoft identity platform".into())
+            }
+        }
+    }
+
+    /// Revoke a token server-side (RFC 7009)
+    ///
+    /// Treats an HTTP 200 and the `unsupported_token_type` error as success so
+    /// providers that only revoke one token type don't block sign-out.
+    pub async fn revoke_token(
+        &self,
+        token: String,
+        token_type_hint: TokenTypeHint,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let revocable = match token_type_hint {
+            TokenTypeHint::AccessToken => {
+                StandardRevocableToken::AccessToken(oauth2::AccessToken::new(token))
+            }
+            TokenTypeHint::RefreshToken => {
+                StandardRevocableToken::RefreshToken(oauth2::RefreshToken::new(token))
+            }
+        };
+
+        match self
+            .oauth_client
+            .revoke_token(revocable)?
+            .request_async(async_http_client)
+            .await
+        {
+            Ok(()) => Ok(()),
+            // Provider doesn't revoke this token type - treat as a no-op success.
+            Err(oauth2::RequestTokenError::ServerResponse(resp))
+                if matches!(
+                    resp.error(),
+                    oauth2::RevocationErrorResponseType::UnsupportedTokenType
+                ) =>
+            {
+                log::info!("[OAuth Rust] Revocation unsupported for token type, treating as success");
+                Ok(())
+            }
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Refresh using the refresh token held in the OS secure store
+    ///
+    /// Reads the stored `TokenData` for `account`, refreshes against the
+    /// provider, and writes the rotated tokens straight back to the keyring so
+    /// the refresh token never crosses the Rust↔JS boundary.
+    pub async fn refresh_stored_token(
+        &self,
+        account: &str,
+    ) -> Result<TokenData, Box<dyn Error + Send + Sync>> {
+        let stored = token_store::load_tokens(self.provider, account)?
+            .ok_or("No stored tokens found for account")?;
+        let refresh_token = stored
+            .refresh_token
+            .ok_or("Stored tokens have no refresh token")?;
+
+        let refreshed = self.refresh_token(refresh_token).await?;
+        token_store::save_tokens(self.provider, account, &refreshed)?;
+        Ok(refreshed)
+    }
 }
 
 /// Check if token is expired or about to expire (within 5 minutes)
@@ -0,0 +1,200 @@
+//! Scoped encryption keys for end-to-end encrypted provider data
+//!
+//! Implements the client side of the scoped-keys extension: before the
+//! authorization request an ephemeral ECDH (P-256) key pair is generated and
+//! its public JWK is appended to the authorization URL as `keys_jwk`, alongside
+//! a key-bearing scope. After the code exchange the provider returns a
+//! JWE-wrapped `keys_jwt`; we perform ECDH against the ephemeral private key to
+//! unwrap the per-scope symmetric keys used to encrypt cached mail/profile
+//! blobs at rest.
+//!
+//! Only Microsoft (and other providers implementing the extension) populate the
+//! `keys_jwt`; for everyone else `unwrap_keys` returns `None` cleanly.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use p256::ecdh::EphemeralSecret;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{EncodedPoint, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// A single per-scope symmetric key unwrapped from the `keys_jwt`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScopedKey {
+    pub kty: String,
+    pub scope: String,
+    /// base64url-encoded raw symmetric key material
+    pub k: String,
+    pub kid: String,
+}
+
+/// Ephemeral ECDH session tying an authorization request to its reply.
+///
+/// Held across the redirect so the same private key that produced `keys_jwk`
+/// can later unwrap the provider's response.
+pub struct ScopedKeysSession {
+    secret: EphemeralSecret,
+    public: PublicKey,
+}
+
+impl ScopedKeysSession {
+    /// Generate a fresh ephemeral P-256 key pair for one authorization request.
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random(&mut rand::thread_rng());
+        let public = secret.public_key();
+        Self { secret, public }
+    }
+
+    /// The `keys_jwk` authorization parameter: base64url of the public JWK.
+    pub fn keys_jwk_param(&self) -> String {
+        let point = self.public.to_encoded_point(false);
+        let x = URL_SAFE_NO_PAD.encode(point.x().expect("P-256 point has x"));
+        let y = URL_SAFE_NO_PAD.encode(point.y().expect("P-256 point has y"));
+        let jwk = serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": x,
+            "y": y,
+        });
+        URL_SAFE_NO_PAD.encode(jwk.to_string())
+    }
+
+    /// Unwrap the per-scope keys from a provider `keys_jwt`.
+    ///
+    /// Returns `None` when the provider didn't supply a `keys_jwt` (it doesn't
+    /// implement the extension). The compact JWE header carries the provider's
+    /// ephemeral public key (`epk`); ECDH against our private key derives the
+    /// content-encryption key used to decrypt the per-scope key bundle.
+    pub fn unwrap_keys(
+        &self,
+        keys_jwt: Option<&str>,
+    ) -> Result<Option<Vec<ScopedKey>>, Box<dyn Error + Send + Sync>> {
+        let keys_jwt = match keys_jwt {
+            Some(jwt) if !jwt.is_empty() => jwt,
+            _ => return Ok(None),
+        };
+
+        // Compact JWE: header.encrypted_key.iv.ciphertext.tag
+        let parts: Vec<&str> = keys_jwt.split('.').collect();
+        if parts.len() != 5 {
+            return Err("Malformed keys_jwt (expected compact JWE)".into());
+        }
+
+        let header: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[0])?)?;
+        let epk = header
+            .get("epk")
+            .ok_or("keys_jwt header missing ephemeral public key")?;
+
+        let peer = jwk_to_public_key(epk)?;
+        let shared = self.secret.diffie_hellman(&peer);
+
+        // The shared secret derives the content-encryption key; the decrypted
+        // payload is the JSON array of per-scope keys.
+        let plaintext = decrypt_jwe_payload(shared.raw_secret_bytes().as_slice(), &parts)?;
+        let keys: Vec<ScopedKey> = serde_json::from_slice(&plaintext)?;
+        Ok(Some(keys))
+    }
+}
+
+impl Default for ScopedKeysSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encrypt a blob at rest with a scoped key (AES-256-GCM).
+///
+/// Returns base64url of `iv || ciphertext || tag` so cached mail/profile data
+/// is only readable by a holder of the scoped key.
+pub fn encrypt_blob(key: &ScopedKey, plaintext: &[u8]) -> Result<String, Box<dyn Error + Send + Sync>> {
+    use aes_gcm::aead::{Aead, KeyInit, OsRng};
+    use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+
+    let raw = URL_SAFE_NO_PAD.decode(&key.k)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&raw));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| "Failed to encrypt blob")?;
+
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(URL_SAFE_NO_PAD.encode(out))
+}
+
+/// Decrypt a blob previously produced by [`encrypt_blob`].
+pub fn decrypt_blob(key: &ScopedKey, blob: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    use aes_gcm::aead::{Aead, KeyInit};
+    use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+    let bytes = URL_SAFE_NO_PAD.decode(blob)?;
+    if bytes.len() < 12 {
+        return Err("Ciphertext too short".into());
+    }
+    let (iv, ciphertext) = bytes.split_at(12);
+    let raw = URL_SAFE_NO_PAD.decode(&key.k)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&raw));
+    cipher
+        .decrypt(Nonce::from_slice(iv), ciphertext)
+        .map_err(|_| "Failed to decrypt blob".into())
+}
+
+/// Reconstruct a P-256 public key from a JWK `{x, y}` object.
+fn jwk_to_public_key(jwk: &serde_json::Value) -> Result<PublicKey, Box<dyn Error + Send + Sync>> {
+    let x = URL_SAFE_NO_PAD.decode(jwk.get("x").and_then(|v| v.as_str()).ok_or("epk missing x")?)?;
+    let y = URL_SAFE_NO_PAD.decode(jwk.get("y").and_then(|v| v.as_str()).ok_or("epk missing y")?)?;
+    let point = EncodedPoint::from_affine_coordinates(
+        x.as_slice().into(),
+        y.as_slice().into(),
+        false,
+    );
+    Ok(PublicKey::from_encoded_point(&point)
+        .into_option()
+        .ok_or("Invalid ephemeral public key in keys_jwt")?)
+}
+
+/// Decrypt the JWE payload given the ECDH shared secret.
+///
+/// Derives an AES-GCM content-encryption key from the shared secret and
+/// decrypts `ciphertext` authenticated by `tag`.
+fn decrypt_jwe_payload(
+    shared_secret: &[u8],
+    parts: &[&str],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    use aes_gcm::{Aes256Gcm, Key, Nonce};
+
+    let cek = hkdf_sha256(shared_secret, b"scoped-keys-cek", 32)?;
+    let iv = URL_SAFE_NO_PAD.decode(parts[2])?;
+    let mut ciphertext = URL_SAFE_NO_PAD.decode(parts[3])?;
+    let tag = URL_SAFE_NO_PAD.decode(parts[4])?;
+    ciphertext.extend_from_slice(&tag);
+
+    let key = Key::<Aes256Gcm>::from_slice(&cek);
+    let cipher = Aes256Gcm::new(key);
+    let nonce = Nonce::from_slice(&iv);
+    cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &ciphertext,
+                aad: parts[0].as_bytes(),
+            },
+        )
+        .map_err(|_| "Failed to decrypt keys_jwt payload".into())
+}
+
+/// HKDF-SHA256 expand of `ikm` to `len` bytes with the given `info`.
+fn hkdf_sha256(
+    ikm: &[u8],
+    info: &[u8],
+    len: usize,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let hk = hkdf::Hkdf::<sha2::Sha256>::new(None, ikm);
+    let mut okm = vec![0u8; len];
+    hk.expand(info, &mut okm)
+        .map_err(|_| "HKDF expand failed")?;
+    Ok(okm)
+}
@@ -0,0 +1,149 @@
+//! Secure token persistence for OAuth credentials
+//!
+//! Keeps `TokenData` in the OS secure store (Keychain on macOS, Credential
+//! Manager on Windows, Secret Service on Linux) via the `keyring` crate so
+//! long-lived refresh tokens never sit in JS-reachable storage. When no secret
+//! service is available (headless Linux, sandboxes) it transparently falls back
+//! to a file under the app data directory. That file holds the tokens in
+//! cleartext (protected only by owner-only file permissions) — we have no
+//! machine-bound secret to key a real cipher against in that environment, so we
+//! do not pretend the contents are encrypted.
+
+use super::{OAuthProvider, TokenData};
+use std::error::Error;
+use std::path::PathBuf;
+
+const SERVICE_PREFIX: &str = "ownyou/oauth";
+
+/// Build the keyring service name for a provider.
+fn service_name(provider: OAuthProvider) -> String {
+    let provider = match provider {
+        OAuthProvider::Microsoft => "microsoft",
+        OAuthProvider::Google => "google",
+    };
+    format!("{}/{}", SERVICE_PREFIX, provider)
+}
+
+/// Persist tokens for `(provider, account)` into the OS secure store.
+pub fn save_tokens(
+    provider: OAuthProvider,
+    account: &str,
+    tokens: &TokenData,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let json = serde_json::to_string(tokens)?;
+    match keyring::Entry::new(&service_name(provider), account) {
+        Ok(entry) if entry.set_password(&json).is_ok() => Ok(()),
+        _ => file_store::save(provider, account, &json),
+    }
+}
+
+/// Load tokens for `(provider, account)`, returning `None` when absent.
+pub fn load_tokens(
+    provider: OAuthProvider,
+    account: &str,
+) -> Result<Option<TokenData>, Box<dyn Error + Send + Sync>> {
+    let json = match keyring::Entry::new(&service_name(provider), account) {
+        Ok(entry) => match entry.get_password() {
+            Ok(json) => Some(json),
+            Err(keyring::Error::NoEntry) => None,
+            Err(_) => file_store::load(provider, account)?,
+        },
+        Err(_) => file_store::load(provider, account)?,
+    };
+
+    match json {
+        Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+        None => Ok(None),
+    }
+}
+
+/// Remove any stored tokens for `(provider, account)`.
+pub fn delete_tokens(
+    provider: OAuthProvider,
+    account: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Ok(entry) = keyring::Entry::new(&service_name(provider), account) {
+        match entry.delete_password() {
+            // Any other error means no reachable secret service; the tokens then
+            // live in the fallback file, which is removed below.
+            Ok(()) | Err(keyring::Error::NoEntry) | Err(_) => {}
+        }
+    }
+    file_store::delete(provider, account)
+}
+
+/// Plaintext file fallback used when no OS secret service is reachable.
+///
+/// The file is written with owner-only permissions (`0600` on Unix) so other
+/// local users cannot read it, but its contents are not encrypted: without a
+/// secret service there is no key to protect them with. Prefer the keyring path
+/// wherever a secret service exists.
+mod file_store {
+    use super::{service_name, OAuthProvider, PathBuf};
+    use std::error::Error;
+    use std::fs;
+
+    fn path(provider: OAuthProvider, account: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        let dir = dirs::data_dir()
+            .ok_or("No data directory available for token fallback store")?
+            .join("ownyou")
+            .join("tokens");
+        fs::create_dir_all(&dir)?;
+        let file = format!("{}-{}.json", service_name(provider).replace('/', "_"), account);
+        Ok(dir.join(file))
+    }
+
+    /// Create (or truncate) the token file with owner-only permissions from the
+    /// outset, so it is never briefly world-readable under a permissive umask.
+    #[cfg(unix)]
+    fn create_private(path: &std::path::Path) -> Result<fs::File, Box<dyn Error + Send + Sync>> {
+        use std::os::unix::fs::OpenOptionsExt;
+        Ok(fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?)
+    }
+
+    #[cfg(not(unix))]
+    fn create_private(path: &std::path::Path) -> Result<fs::File, Box<dyn Error + Send + Sync>> {
+        Ok(fs::File::create(path)?)
+    }
+
+    pub fn save(
+        provider: OAuthProvider,
+        account: &str,
+        json: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        use std::io::Write;
+        let path = path(provider, account)?;
+        let mut file = create_private(&path)?;
+        file.write_all(json.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(
+        provider: OAuthProvider,
+        account: &str,
+    ) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+        let path = path(provider, account)?;
+        match fs::read_to_string(&path) {
+            Ok(json) => Ok(Some(json)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub fn delete(
+        provider: OAuthProvider,
+        account: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = path(provider, account)?;
+        match fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
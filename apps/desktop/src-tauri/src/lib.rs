@@ -7,21 +7,34 @@
 
 pub mod oauth;
 
-use oauth::{OAuthClient, OAuthProvider, TokenData, is_token_expired};
+use oauth::token_manager::TokenManager;
+use oauth::{
+    token_store, DeviceFlow, IntrospectInfo, OAuthClient, OAuthConfig, OAuthProvider,
+    PublicTokenData, TokenData,
+    is_token_expired,
+};
 use oauth2::PkceCodeVerifier;
+use std::collections::HashMap;
 use std::sync::Mutex;
 use tauri::{Emitter, Listener, State};
 
+/// Time-to-live for a pending OAuth flow before it is evicted (10 minutes, ms).
+const PENDING_FLOW_TTL_MS: i64 = 10 * 60 * 1000;
+
+/// A PKCE/CSRF flow awaiting its authorization-code callback.
+struct PendingFlow {
+    provider: OAuthProvider,
+    pkce_verifier: PkceCodeVerifier,
+    created_at: i64,
+}
+
 /// Application state for OAuth flow
 struct AppState {
-    /// PKCE verifier for Microsoft OAuth
-    microsoft_pkce_verifier: Mutex<Option<PkceCodeVerifier>>,
-    /// PKCE verifier for Google OAuth
-    google_pkce_verifier: Mutex<Option<PkceCodeVerifier>>,
-    /// CSRF state for Microsoft OAuth
-    microsoft_csrf_state: Mutex<Option<String>>,
-    /// CSRF state for Google OAuth
-    google_csrf_state: Mutex<Option<String>>,
+    /// Pending OAuth flows keyed by their CSRF `state` value, so concurrent
+    /// logins don't clobber each other's PKCE verifier.
+    pending_flows: Mutex<HashMap<String, PendingFlow>>,
+    /// Background token manager owning cached tokens and refresh serialization
+    token_manager: TokenManager,
 }
 
 /// Greet command - example Tauri command
@@ -39,30 +52,29 @@ async fn start_oauth(
     provider: OAuthProvider,
     client_id: String,
     redirect_uri: String,
+    config: Option<OAuthConfig>,
     state: State<'_, AppState>,
 ) -> Result<String, String> {
-    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
-        .map_err(|e| e.to_string())?;
+    let oauth_client =
+        OAuthClient::new_with_config(provider, client_id, None, redirect_uri, config.unwrap_or_default())
+            .map_err(|e| e.to_string())?;
 
     let (auth_url, pkce_verifier, csrf_token) = oauth_client
         .get_authorization_url()
         .map_err(|e| e.to_string())?;
 
-    // Store PKCE verifier and CSRF state based on provider
-    match provider {
-        OAuthProvider::Microsoft => {
-            let mut verifier_lock = state.microsoft_pkce_verifier.lock().unwrap();
-            *verifier_lock = Some(pkce_verifier);
-            let mut csrf_lock = state.microsoft_csrf_state.lock().unwrap();
-            *csrf_lock = Some(csrf_token.secret().clone());
-        }
-        OAuthProvider::Google => {
-            let mut verifier_lock = state.google_pkce_verifier.lock().unwrap();
-            *verifier_lock = Some(pkce_verifier);
-            let mut csrf_lock = state.google_csrf_state.lock().unwrap();
-            *csrf_lock = Some(csrf_token.secret().clone());
-        }
-    }
+    // Key the pending flow by its CSRF state so concurrent logins coexist.
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut flows = state.pending_flows.lock().unwrap();
+    flows.retain(|_, flow| now - flow.created_at < PENDING_FLOW_TTL_MS);
+    flows.insert(
+        csrf_token.secret().clone(),
+        PendingFlow {
+            provider,
+            pkce_verifier,
+            created_at: now,
+        },
+    );
 
     Ok(auth_url)
 }
@@ -77,61 +89,98 @@ async fn complete_oauth(
     redirect_uri: String,
     code: String,
     received_state: Option<String>,
+    config: Option<OAuthConfig>,
     state: State<'_, AppState>,
-) -> Result<TokenData, String> {
-    // Verify CSRF state if provided
-    let expected_state = match provider {
-        OAuthProvider::Microsoft => {
-            let csrf_lock = state.microsoft_csrf_state.lock().unwrap();
-            csrf_lock.clone()
-        }
-        OAuthProvider::Google => {
-            let csrf_lock = state.google_csrf_state.lock().unwrap();
-            csrf_lock.clone()
-        }
+) -> Result<PublicTokenData, String> {
+    // The CSRF state is now mandatory: reject any callback we can't match to a
+    // pending flow we started.
+    let received_state = received_state.ok_or("Missing CSRF state in callback")?;
+
+    let pending = {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut flows = state.pending_flows.lock().unwrap();
+        flows.retain(|_, flow| now - flow.created_at < PENDING_FLOW_TTL_MS);
+        flows
+            .remove(&received_state)
+            .ok_or("CSRF state mismatch - possible attack")?
     };
 
-    if let Some(ref received) = received_state {
-        if let Some(ref expected) = expected_state {
-            if received != expected {
-                return Err("CSRF state mismatch - possible attack".to_string());
-            }
-        }
+    if pending.provider != provider {
+        return Err("CSRF state provider mismatch - possible attack".to_string());
     }
 
-    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
-        .map_err(|e| e.to_string())?;
-
-    // Retrieve stored PKCE verifier based on provider
-    let pkce_verifier = match provider {
-        OAuthProvider::Microsoft => {
-            let mut verifier_lock = state.microsoft_pkce_verifier.lock().unwrap();
-            verifier_lock.take().ok_or("No PKCE verifier found for Microsoft")?
-        }
-        OAuthProvider::Google => {
-            let mut verifier_lock = state.google_pkce_verifier.lock().unwrap();
-            verifier_lock.take().ok_or("No PKCE verifier found for Google")?
-        }
-    };
+    let oauth_client =
+        OAuthClient::new_with_config(provider, client_id, None, redirect_uri, config.unwrap_or_default())
+            .map_err(|e| e.to_string())?;
 
     let token_data = oauth_client
-        .exchange_code(code, pkce_verifier)
+        .exchange_code(code, pending.pkce_verifier)
         .await
         .map_err(|e| e.to_string())?;
 
-    // Clear CSRF state
-    match provider {
-        OAuthProvider::Microsoft => {
-            let mut csrf_lock = state.microsoft_csrf_state.lock().unwrap();
-            *csrf_lock = None;
-        }
-        OAuthProvider::Google => {
-            let mut csrf_lock = state.google_csrf_state.lock().unwrap();
-            *csrf_lock = None;
-        }
+    // Persist directly into the OS keyring so the refresh token never has to be
+    // handed to the WebView for plaintext storage.
+    if let Err(e) = token_store::save_tokens(provider, &token_data) {
+        eprintln!("[OAuth] Failed to persist tokens to keyring: {}", e);
     }
 
-    Ok(token_data)
+    // Hand the WebView only the access token and its expiry; the refresh token
+    // stays in the keyring and is served by `get_valid_access_token`.
+    Ok(PublicTokenData::from(&token_data))
+}
+
+/// Persist tokens for a provider in the OS secure store
+#[tauri::command]
+fn save_tokens(provider: OAuthProvider, tokens: TokenData) -> Result<(), String> {
+    token_store::save_tokens(provider, &tokens).map_err(|e| e.to_string())
+}
+
+/// Load tokens for a provider from the OS secure store
+#[tauri::command]
+fn load_tokens(provider: OAuthProvider) -> Result<Option<TokenData>, String> {
+    token_store::load_tokens(provider).map_err(|e| e.to_string())
+}
+
+/// Clear any stored tokens for a provider
+#[tauri::command]
+fn clear_tokens(provider: OAuthProvider) -> Result<(), String> {
+    token_store::clear_tokens(provider).map_err(|e| e.to_string())
+}
+
+/// Introspect an access token server-side (RFC 7662)
+#[tauri::command]
+async fn introspect_token(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    token: String,
+) -> Result<IntrospectInfo, String> {
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    oauth_client
+        .introspect(token)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Revoke a refresh token server-side and clear it from the keyring
+#[tauri::command]
+async fn revoke_token(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    token: String,
+) -> Result<(), String> {
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    oauth_client
+        .revoke_token(token)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    token_store::clear_tokens(provider).map_err(|e| e.to_string())
 }
 
 /// Refresh access token using refresh token
@@ -159,15 +208,68 @@ fn check_token_expiration(token_data: TokenData) -> Result<bool, String> {
     Ok(is_token_expired(&token_data))
 }
 
+/// Return a valid access token for a provider, refreshing in the background
+///
+/// Replaces the manual `check_token_expiration` + `refresh_access_token`
+/// dance: the token manager checks expiry, refreshes if needed (serializing
+/// concurrent refreshes), and returns a currently valid access token.
+#[tauri::command]
+async fn get_valid_access_token(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    state
+        .token_manager
+        .get_valid_access_token(provider, client_id, redirect_uri)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Begin an OAuth 2.0 Device Authorization Grant for headless/kiosk logins
+#[tauri::command]
+async fn start_device_oauth(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+) -> Result<DeviceFlow, String> {
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    oauth_client
+        .start_device_flow(scopes)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Poll the device-flow token endpoint until the user approves or it expires
+#[tauri::command]
+async fn poll_device_oauth(
+    provider: OAuthProvider,
+    client_id: String,
+    redirect_uri: String,
+    device_code: String,
+    interval: u64,
+    expires_in: u64,
+) -> Result<TokenData, String> {
+    let oauth_client = OAuthClient::new(provider, client_id, None, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    oauth_client
+        .poll_device_token(device_code, interval, expires_in)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 /// Main library entry point for Tauri
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .manage(AppState {
-            microsoft_pkce_verifier: Mutex::new(None),
-            google_pkce_verifier: Mutex::new(None),
-            microsoft_csrf_state: Mutex::new(None),
-            google_csrf_state: Mutex::new(None),
+            pending_flows: Mutex::new(HashMap::new()),
+            token_manager: TokenManager::default(),
         })
         .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_opener::init())
@@ -196,7 +298,15 @@ pub fn run() {
             start_oauth,
             complete_oauth,
             refresh_access_token,
-            check_token_expiration
+            check_token_expiration,
+            start_device_oauth,
+            poll_device_oauth,
+            save_tokens,
+            load_tokens,
+            clear_tokens,
+            get_valid_access_token,
+            revoke_token,
+            introspect_token
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
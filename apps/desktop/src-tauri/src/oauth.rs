@@ -6,20 +6,46 @@
 use serde::{Deserialize, Serialize};
 use oauth2::{
     AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
-    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl,
+    PkceCodeVerifier, RedirectUrl, RevocationUrl, Scope, StandardRevocableToken, TokenResponse,
+    TokenUrl,
 };
 use oauth2::basic::BasicClient;
 use oauth2::reqwest::async_http_client;
 use chrono::Utc;
 use std::error::Error;
 
+pub mod token_manager;
+pub mod token_store;
+
 // Microsoft OAuth endpoints
-const MS_AUTH_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/authorize";
 const MS_TOKEN_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/token";
+const MS_DEVICE_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/devicecode";
+
+/// Default Microsoft tenant when a caller doesn't override it.
+const MS_DEFAULT_TENANT: &str = "common";
+
+/// Build the Microsoft authorize endpoint for a given tenant (e.g. `common`,
+/// `consumers`, `organizations`, or a directory GUID).
+fn ms_auth_url(tenant: &str) -> String {
+    format!("https://login.microsoftonline.com/{}/oauth2/v2.0/authorize", tenant)
+}
+
+/// Build the Microsoft token endpoint for a given tenant.
+fn ms_token_url(tenant: &str) -> String {
+    format!("https://login.microsoftonline.com/{}/oauth2/v2.0/token", tenant)
+}
 
 // Google OAuth endpoints
 const GOOGLE_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const GOOGLE_DEVICE_URL: &str = "https://oauth2.googleapis.com/device/code";
+
+// Token revocation endpoints
+const MS_REVOCATION_URL: &str = "https://login.microsoftonline.com/common/oauth2/v2.0/logout";
+const GOOGLE_REVOCATION_URL: &str = "https://oauth2.googleapis.com/revoke";
+
+// Token introspection endpoints
+const GOOGLE_TOKENINFO_URL: &str = "https://oauth2.googleapis.com/tokeninfo";
 
 /// Provider type for OAuth
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
@@ -40,29 +66,176 @@ pub struct TokenData {
     pub token_type: String,
 }
 
+/// Refresh-token-stripped view of [`TokenData`] for the WebView
+///
+/// `complete_oauth` persists the refresh token in the OS keyring and returns
+/// this view, so the login flow does not hand the refresh token to the JS
+/// bridge; the frontend only needs the access token and its expiry, and can
+/// ask the token manager for a fresh access token once this one lapses.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicTokenData {
+    pub access_token: String,
+    pub expires_at: i64,
+    pub scope: String,
+    pub token_type: String,
+}
+
+impl From<&TokenData> for PublicTokenData {
+    fn from(t: &TokenData) -> Self {
+        Self {
+            access_token: t.access_token.clone(),
+            expires_at: t.expires_at,
+            scope: t.scope.clone(),
+            token_type: t.token_type.clone(),
+        }
+    }
+}
+
+/// Device authorization response for the device-code login flow
+///
+/// Returned by `start_device_flow` so the frontend can display the user code
+/// and verification URI while `poll_device_token` runs in Rust.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceFlow {
+    pub user_code: String,
+    pub verification_uri: String,
+    pub device_code: String,
+    pub interval: u64,
+    pub expires_in: u64,
+}
+
+/// Raw device authorization endpoint response
+#[derive(Debug, Deserialize)]
+struct DeviceAuthResponse {
+    device_code: String,
+    user_code: String,
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    #[serde(default = "default_interval")]
+    interval: u64,
+    expires_in: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+/// Raw token endpoint response while polling the device flow
+#[derive(Debug, Deserialize)]
+struct DeviceTokenResponse {
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+    scope: Option<String>,
+    token_type: Option<String>,
+    error: Option<String>,
+}
+
+/// Result of an RFC 7662 token introspection request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IntrospectInfo {
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub scope: String,
+    #[serde(default)]
+    pub exp: i64,
+    #[serde(default)]
+    pub sub: Option<String>,
+}
+
+/// Raw Google `tokeninfo` response fields we care about
+#[derive(Debug, Deserialize)]
+struct GoogleTokenInfo {
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    sub: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Caller-supplied OAuth configuration overrides
+///
+/// Lets a caller request scopes beyond the Mail.Read/User.Read defaults, target
+/// a specific Microsoft tenant (`common`, `consumers`, `organizations`, or a
+/// directory GUID), and pass provider-specific authorization parameters.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthConfig {
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub extra_auth_params: Vec<(String, String)>,
+}
+
+/// Default scopes for a provider when the caller doesn't specify any.
+fn default_scopes(provider: OAuthProvider) -> Vec<String> {
+    match provider {
+        OAuthProvider::Microsoft => vec![
+            "offline_access".to_string(),
+            "https://graph.microsoft.com/Mail.Read".to_string(),
+            "https://graph.microsoft.com/User.Read".to_string(),
+        ],
+        OAuthProvider::Google => vec![
+            "https://www.googleapis.com/auth/gmail.readonly".to_string(),
+            "https://www.googleapis.com/auth/userinfo.email".to_string(),
+        ],
+    }
+}
+
 /// OAuth client that works for both Microsoft and Google
 pub struct OAuthClient {
     provider: OAuthProvider,
     client_id: String,
     redirect_uri: String,
+    config: OAuthConfig,
     oauth_client: BasicClient,
 }
 
 impl OAuthClient {
-    /// Create a new OAuth client
+    /// Create a new OAuth client with default scopes and tenant
     pub fn new(
         provider: OAuthProvider,
         client_id: String,
         client_secret: Option<String>,
         redirect_uri: String,
     ) -> Result<Self, Box<dyn Error>> {
+        Self::new_with_config(
+            provider,
+            client_id,
+            client_secret,
+            redirect_uri,
+            OAuthConfig::default(),
+        )
+    }
+
+    /// Create a new OAuth client, honoring caller-supplied scopes/tenant/params
+    pub fn new_with_config(
+        provider: OAuthProvider,
+        client_id: String,
+        client_secret: Option<String>,
+        redirect_uri: String,
+        config: OAuthConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        let tenant = config.tenant.clone().unwrap_or_else(|| MS_DEFAULT_TENANT.to_string());
         let (auth_url, token_url) = match provider {
-            OAuthProvider::Microsoft => (MS_AUTH_URL, MS_TOKEN_URL),
-            OAuthProvider::Google => (GOOGLE_AUTH_URL, GOOGLE_TOKEN_URL),
+            OAuthProvider::Microsoft => (ms_auth_url(&tenant), ms_token_url(&tenant)),
+            OAuthProvider::Google => (GOOGLE_AUTH_URL.to_string(), GOOGLE_TOKEN_URL.to_string()),
         };
 
-        let auth_url = AuthUrl::new(auth_url.to_string())?;
-        let token_url = TokenUrl::new(token_url.to_string())?;
+        let auth_url = AuthUrl::new(auth_url)?;
+        let token_url = TokenUrl::new(token_url)?;
+
+        let revocation_url = match provider {
+            OAuthProvider::Microsoft => MS_REVOCATION_URL,
+            OAuthProvider::Google => GOOGLE_REVOCATION_URL,
+        };
 
         let oauth_client = BasicClient::new(
             ClientId::new(client_id.clone()),
@@ -70,12 +243,14 @@ impl OAuthClient {
             auth_url,
             Some(token_url),
         )
-        .set_redirect_uri(RedirectUrl::new(redirect_uri.clone())?);
+        .set_redirect_uri(RedirectUrl::new(redirect_uri.clone())?)
+        .set_revocation_uri(RevocationUrl::new(revocation_url.to_string())?);
 
         Ok(Self {
             provider,
             client_id,
             redirect_uri,
+            config,
             oauth_client,
         })
     }
@@ -88,19 +263,19 @@ impl OAuthClient {
             .authorize_url(CsrfToken::new_random)
             .set_pkce_challenge(pkce_challenge);
 
-        // Add provider-specific scopes
-        match self.provider {
-            OAuthProvider::Microsoft => {
-                auth_builder = auth_builder
-                    .add_scope(Scope::new("offline_access".to_string()))
-                    .add_scope(Scope::new("https://graph.microsoft.com/Mail.Read".to_string()))
-                    .add_scope(Scope::new("https://graph.microsoft.com/User.Read".to_string()));
-            }
-            OAuthProvider::Google => {
-                auth_builder = auth_builder
-                    .add_scope(Scope::new("https://www.googleapis.com/auth/gmail.readonly".to_string()))
-                    .add_scope(Scope::new("https://www.googleapis.com/auth/userinfo.email".to_string()));
-            }
+        // Use caller-supplied scopes, falling back to the provider defaults.
+        let scopes = if self.config.scopes.is_empty() {
+            default_scopes(self.provider)
+        } else {
+            self.config.scopes.clone()
+        };
+        for scope in scopes {
+            auth_builder = auth_builder.add_scope(Scope::new(scope));
+        }
+
+        // Provider-specific extra authorization parameters.
+        for (key, value) in &self.config.extra_auth_params {
+            auth_builder = auth_builder.add_extra_param(key, value);
         }
 
         let (auth_url, csrf_token) = auth_builder.url();
@@ -114,6 +289,114 @@ impl OAuthClient {
         Ok((url_string, pkce_verifier, csrf_token))
     }
 
+    /// Start an OAuth 2.0 Device Authorization Grant
+    ///
+    /// POSTs the client id and scopes to the provider device authorization
+    /// endpoint and returns the codes needed to prompt the user and poll.
+    pub async fn start_device_flow(&self, scopes: Vec<String>) -> Result<DeviceFlow, Box<dyn Error>> {
+        let device_url = match self.provider {
+            OAuthProvider::Microsoft => MS_DEVICE_URL,
+            OAuthProvider::Google => GOOGLE_DEVICE_URL,
+        };
+
+        let scope = scopes.join(" ");
+        let params = [
+            ("client_id", self.client_id.as_str()),
+            ("scope", scope.as_str()),
+        ];
+
+        let response: DeviceAuthResponse = reqwest::Client::new()
+            .post(device_url)
+            .form(&params)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(DeviceFlow {
+            user_code: response.user_code,
+            verification_uri: response.verification_uri,
+            device_code: response.device_code,
+            interval: response.interval,
+            expires_in: response.expires_in,
+        })
+    }
+
+    /// Poll the token endpoint until the user approves the device flow
+    ///
+    /// Honors `authorization_pending` (keep waiting) and `slow_down` (increase
+    /// the interval by 5s); errors on `expired_token`/`access_denied`. Stops
+    /// once `expires_in` seconds have elapsed so a provider that never returns
+    /// `expired_token` can't keep us polling forever. Yields the same
+    /// `TokenData` as `exchange_code`.
+    pub async fn poll_device_token(
+        &self,
+        device_code: String,
+        interval: u64,
+        expires_in: u64,
+    ) -> Result<TokenData, Box<dyn Error>> {
+        let token_url = match self.provider {
+            OAuthProvider::Microsoft => MS_TOKEN_URL,
+            OAuthProvider::Google => GOOGLE_TOKEN_URL,
+        };
+
+        let mut interval = interval.max(1);
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(expires_in);
+        let client = reqwest::Client::new();
+
+        loop {
+            if tokio::time::Instant::now() >= deadline {
+                return Err("Device code expired".into());
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+            let params = [
+                ("client_id", self.client_id.as_str()),
+                ("device_code", device_code.as_str()),
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ];
+
+            let response: DeviceTokenResponse = client
+                .post(token_url)
+                .form(&params)
+                .send()
+                .await?
+                .json()
+                .await?;
+
+            if let Some(error) = response.error {
+                match error.as_str() {
+                    "authorization_pending" => continue,
+                    "slow_down" => {
+                        interval += 5;
+                        continue;
+                    }
+                    "expired_token" => return Err("Device code expired".into()),
+                    "access_denied" => return Err("Access denied by user".into()),
+                    other => return Err(format!("Device flow error: {}", other).into()),
+                }
+            }
+
+            let access_token = response
+                .access_token
+                .ok_or("No access token in device token response")?;
+            let refresh_token = response
+                .refresh_token
+                .ok_or("No refresh token received")?;
+            let expires_in_secs = response.expires_in.unwrap_or(3600);
+            let expires_at = Utc::now().timestamp_millis() + (expires_in_secs * 1000);
+
+            return Ok(TokenData {
+                access_token,
+                refresh_token,
+                expires_at,
+                scope: response.scope.unwrap_or_default(),
+                token_type: response.token_type.unwrap_or_else(|| "Bearer".to_string()),
+            });
+        }
+    }
+
     /// Exchange authorization code for tokens
     pub async fn exchange_code(
         &self,
@@ -166,6 +449,74 @@ impl OAuthClient {
         })
     }
 
+    /// Introspect an access token server-side
+    ///
+    /// The Microsoft identity platform exposes no RFC 7662 introspection
+    /// endpoint for these tokens, so only Google is supported, via its
+    /// `tokeninfo` endpoint (a `GET` with the token in the query string). An
+    /// active token yields a JSON body with `scope`/`sub`; an invalid one yields
+    /// an `error`, which we map to `active = false`.
+    pub async fn introspect(&self, token: String) -> Result<IntrospectInfo, Box<dyn Error>> {
+        match self.provider {
+            OAuthProvider::Google => {
+                let resp: GoogleTokenInfo = reqwest::Client::new()
+                    .get(GOOGLE_TOKENINFO_URL)
+                    .query(&[("access_token", token.as_str())])
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+
+                if resp.error.is_some() {
+                    return Ok(IntrospectInfo {
+                        active: false,
+                        scope: String::new(),
+                        exp: 0,
+                        sub: None,
+                    });
+                }
+
+                Ok(IntrospectInfo {
+                    active: true,
+                    scope: resp.scope.unwrap_or_default(),
+                    // `tokeninfo` reports the remaining lifetime rather than an
+                    // absolute `exp`; leave it unset.
+                    exp: 0,
+                    sub: resp.sub,
+                })
+            }
+            OAuthProvider::Microsoft => {
+                Err("token introspection is not supported by the Microsoft identity platform".into())
+            }
+        }
+    }
+
+    /// Revoke a refresh token server-side on sign-out
+    ///
+    /// Treats an `unsupported_token_type` error as success so a provider that
+    /// only revokes one token type doesn't block logout.
+    pub async fn revoke_token(&self, token: String) -> Result<(), Box<dyn Error>> {
+        let revocable = StandardRevocableToken::RefreshToken(oauth2::RefreshToken::new(token));
+
+        match self
+            .oauth_client
+            .revoke_token(revocable)?
+            .request_async(async_http_client)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(oauth2::RequestTokenError::ServerResponse(resp))
+                if matches!(
+                    resp.error(),
+                    oauth2::RevocationErrorResponseType::UnsupportedTokenType
+                ) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
     /// Refresh access token using refresh token
     pub async fn refresh_token(&self, refresh_token: String) -> Result<TokenData, Box<dyn Error>> {
         let refresh_token_clone = refresh_token.clone();
@@ -215,6 +566,15 @@ pub fn is_token_expired(token_data: &TokenData) -> bool {
     token_data.expires_at <= now + buffer
 }
 
+/// Check if a token is close enough to expiry to be worth an introspection
+/// round-trip (within 15 minutes). A fresh token is trusted without the extra
+/// network call.
+pub fn is_token_near_expiry(token_data: &TokenData) -> bool {
+    let now = Utc::now().timestamp_millis();
+    let buffer = 15 * 60 * 1000; // 15 minutes in milliseconds
+    token_data.expires_at <= now + buffer
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -297,4 +657,23 @@ mod tests {
         assert!(auth_url.contains("access_type=offline"));
         assert!(auth_url.contains("prompt=consent"));
     }
+
+    #[test]
+    fn test_device_flow_serialization() {
+        let flow = DeviceFlow {
+            user_code: "ABCD-EFGH".to_string(),
+            verification_uri: "https://microsoft.com/devicelogin".to_string(),
+            device_code: "device-code-123".to_string(),
+            interval: 5,
+            expires_in: 900,
+        };
+
+        let json = serde_json::to_string(&flow).unwrap();
+        assert!(json.contains("userCode"));
+        assert!(json.contains("verificationUri"));
+
+        let deserialized: DeviceFlow = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.user_code, "ABCD-EFGH");
+        assert_eq!(deserialized.interval, 5);
+    }
 }
@@ -0,0 +1,128 @@
+//! Background token manager with automatic refresh and error backoff
+//!
+//! Owns the cached `TokenData` per provider and hands callers a valid access
+//! token, refreshing transparently when needed. Concurrent refreshes for the
+//! same provider are serialized behind a per-provider async mutex so N callers
+//! trigger a single network request; a hard failure enters an error-pending
+//! cooldown during which further attempts short-circuit, and each refresh is
+//! bounded by a timeout so a hung network call can't block callers.
+
+use super::token_store;
+use super::{is_token_expired, is_token_near_expiry, OAuthClient, OAuthProvider, TokenData};
+use chrono::Utc;
+use std::error::Error;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Cooldown after a hard refresh failure during which refreshes are suppressed.
+const ERROR_PENDING_MS: i64 = 60_000;
+
+/// Per-refresh network timeout.
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Network timeout for an out-of-band introspection check.
+const INTROSPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Cached token state for a single provider.
+#[derive(Default)]
+struct ProviderSlot {
+    tokens: Option<TokenData>,
+    /// Timestamp (ms) until which refresh attempts short-circuit.
+    error_pending_until: i64,
+}
+
+/// Manages cached tokens and refresh serialization across providers.
+#[derive(Default)]
+pub struct TokenManager {
+    microsoft: Mutex<ProviderSlot>,
+    google: Mutex<ProviderSlot>,
+}
+
+impl TokenManager {
+    fn slot(&self, provider: OAuthProvider) -> &Mutex<ProviderSlot> {
+        match provider {
+            OAuthProvider::Microsoft => &self.microsoft,
+            OAuthProvider::Google => &self.google,
+        }
+    }
+
+    /// Return a valid access token, refreshing via the stored refresh token if
+    /// the cached token is missing or expired.
+    ///
+    /// Holding the per-provider lock across the refresh serializes concurrent
+    /// callers: the first triggers the network request and the rest observe the
+    /// freshly cached token.
+    pub async fn get_valid_access_token(
+        &self,
+        provider: OAuthProvider,
+        client_id: String,
+        redirect_uri: String,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut slot = self.slot(provider).lock().await;
+
+        // Seed the cache from the keyring on first use.
+        if slot.tokens.is_none() {
+            slot.tokens = token_store::load_tokens(provider)?;
+        }
+
+        if let Some(tokens) = &slot.tokens {
+            if !is_token_expired(tokens) {
+                // Introspection can catch tokens revoked out-of-band while still
+                // within their local expiry, but it costs a network round-trip,
+                // so only pay it as the token approaches expiry; a fresh token is
+                // trusted on the hot path. A negative result forces a refresh.
+                if is_token_near_expiry(tokens) {
+                    let client =
+                        OAuthClient::new(provider, client_id.clone(), None, redirect_uri.clone())?;
+                    // Bound the introspection call so a hung request can't block
+                    // every caller holding on this provider's lock; a timeout or
+                    // error is treated as inconclusive and the token is kept.
+                    let still_active = match tokio::time::timeout(
+                        INTROSPECT_TIMEOUT,
+                        client.introspect(tokens.access_token.clone()),
+                    )
+                    .await
+                    {
+                        Ok(Ok(info)) => info.active,
+                        Ok(Err(_)) | Err(_) => true,
+                    };
+                    if still_active {
+                        return Ok(tokens.access_token.clone());
+                    }
+                } else {
+                    return Ok(tokens.access_token.clone());
+                }
+            }
+        }
+
+        let now = Utc::now().timestamp_millis();
+        if now < slot.error_pending_until {
+            return Err("Refresh error-pending: using stale token unavailable".into());
+        }
+
+        let refresh_token = slot
+            .tokens
+            .as_ref()
+            .map(|t| t.refresh_token.clone())
+            .ok_or("No tokens available to refresh")?;
+
+        let client = OAuthClient::new(provider, client_id, None, redirect_uri)?;
+        match tokio::time::timeout(REFRESH_TIMEOUT, client.refresh_token(refresh_token)).await {
+            Ok(Ok(new_tokens)) => {
+                let access_token = new_tokens.access_token.clone();
+                let _ = token_store::save_tokens(provider, &new_tokens);
+                slot.tokens = Some(new_tokens);
+                slot.error_pending_until = 0;
+                Ok(access_token)
+            }
+            Ok(Err(e)) => {
+                slot.error_pending_until = now + ERROR_PENDING_MS;
+                Err(e)
+            }
+            Err(_) => {
+                slot.error_pending_until = now + ERROR_PENDING_MS;
+                Err("Token refresh timed out".into())
+            }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! OS-keyring-backed token persistence
+//!
+//! Keeps `TokenData` in the OS secure credential store (macOS Keychain /
+//! Windows Credential Manager / libsecret) via the `keyring` crate so refresh
+//! tokens need not be handed to the WebView for storage or land in plaintext
+//! `tauri-plugin-store` JSON on disk. Entries are keyed by `"ownyou/{provider}"`.
+//! The `load_tokens` command still returns the full `TokenData`, refresh token
+//! included.
+
+use super::{OAuthProvider, TokenData};
+use std::error::Error;
+
+const KEYRING_USER: &str = "tokens";
+
+/// Keyring service name for a provider, e.g. `ownyou/microsoft`.
+fn service_name(provider: OAuthProvider) -> String {
+    let provider = match provider {
+        OAuthProvider::Microsoft => "microsoft",
+        OAuthProvider::Google => "google",
+    };
+    format!("ownyou/{}", provider)
+}
+
+/// Persist tokens for a provider into the OS secure store.
+pub fn save_tokens(provider: OAuthProvider, tokens: &TokenData) -> Result<(), Box<dyn Error>> {
+    let entry = keyring::Entry::new(&service_name(provider), KEYRING_USER)?;
+    entry.set_password(&serde_json::to_string(tokens)?)?;
+    Ok(())
+}
+
+/// Load tokens for a provider, returning `None` when nothing is stored.
+pub fn load_tokens(provider: OAuthProvider) -> Result<Option<TokenData>, Box<dyn Error>> {
+    let entry = keyring::Entry::new(&service_name(provider), KEYRING_USER)?;
+    match entry.get_password() {
+        Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Remove any stored tokens for a provider.
+pub fn clear_tokens(provider: OAuthProvider) -> Result<(), Box<dyn Error>> {
+    let entry = keyring::Entry::new(&service_name(provider), KEYRING_USER)?;
+    match entry.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
@@ -1,13 +1,15 @@
 pub mod oauth;
 
-use oauth::{MsalClient, TokenData, is_token_expired};
+use oauth::{DeviceFlow, MsalClient, RefreshManager, TokenData, TokenStore, is_token_expired};
 use oauth2::PkceCodeVerifier;
 use std::sync::Mutex;
 use tauri::State;
 
-// Global state for PKCE verifier
+// Global state for PKCE verifier, OIDC nonce, and refresh coalescing
 struct AppState {
     pkce_verifier: Mutex<Option<PkceCodeVerifier>>,
+    nonce: Mutex<Option<String>>,
+    refresh_manager: RefreshManager,
 }
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
@@ -27,13 +29,13 @@ async fn start_oauth(
     let msal_client = MsalClient::new(client_id, client_secret, redirect_uri)
         .map_err(|e| e.to_string())?;
 
-    let (auth_url, pkce_verifier, _csrf_token) = msal_client
+    let (auth_url, pkce_verifier, _csrf_token, nonce) = msal_client
         .get_authorization_url()
         .map_err(|e| e.to_string())?;
 
-    // Store PKCE verifier for later use
-    let mut verifier_lock = state.pkce_verifier.lock().unwrap();
-    *verifier_lock = Some(pkce_verifier);
+    // Store PKCE verifier and nonce for later use
+    *state.pkce_verifier.lock().unwrap() = Some(pkce_verifier);
+    *state.nonce.lock().unwrap() = Some(nonce);
 
     Ok(auth_url)
 }
@@ -50,52 +52,163 @@ async fn complete_oauth(
     let msal_client = MsalClient::new(client_id, client_secret, redirect_uri)
         .map_err(|e| e.to_string())?;
 
-    // Retrieve stored PKCE verifier
+    // Retrieve stored PKCE verifier and nonce
     let pkce_verifier = {
         let mut verifier_lock = state.pkce_verifier.lock().unwrap();
         verifier_lock.take().ok_or("No PKCE verifier found")?
     };
+    let nonce = {
+        let mut nonce_lock = state.nonce.lock().unwrap();
+        nonce_lock.take().ok_or("No nonce found")?
+    };
 
     let token_data = msal_client
-        .exchange_code(code, pkce_verifier)
+        .exchange_code(code, pkce_verifier, nonce)
         .await
         .map_err(|e| e.to_string())?;
 
-    // Return token data - storage handled by frontend using Tauri Store plugin
+    // Persist into the OS keyring so the long-lived refresh token survives
+    // restarts without landing in plaintext config.
+    let account = account_key(&token_data);
+    if let Err(e) = TokenStore::save("microsoft", &account, &token_data) {
+        eprintln!("[OAuth] Failed to persist tokens to keyring: {}", e);
+    }
+
     Ok(token_data)
 }
 
+/// Keyring account key for a token set, preferring the verified OIDC subject.
+fn account_key(token_data: &TokenData) -> String {
+    token_data
+        .identity
+        .as_ref()
+        .map(|i| i.sub.clone())
+        .unwrap_or_else(|| "default".to_string())
+}
+
 /// Refresh access token
+///
+/// Routed through the shared [`RefreshManager`] so concurrent refreshes for
+/// the same client are coalesced into a single network request, transient
+/// failures are retried with backoff, and a hard failure enters a short
+/// cooldown. Refreshes are keyed by `account`, the same key the rotated tokens
+/// are saved under.
 #[tauri::command]
 async fn refresh_access_token(
     client_id: String,
     client_secret: Option<String>,
     redirect_uri: String,
     refresh_token: String,
+    account: String,
+    state: State<'_, AppState>,
 ) -> Result<TokenData, String> {
-    let msal_client = MsalClient::new(client_id, client_secret, redirect_uri)
+    let msal_client = MsalClient::new(client_id.clone(), client_secret, redirect_uri)
         .map_err(|e| e.to_string())?;
 
-    let new_token = msal_client
-        .refresh_token(refresh_token)
+    let new_token = state
+        .refresh_manager
+        .refresh(&msal_client, &account, refresh_token)
         .await
         .map_err(|e| e.to_string())?;
 
-    // Return new token data - storage handled by frontend
+    // Persist the rotated tokens back to the keyring so the refreshed refresh
+    // token replaces the stale one across restarts.
+    if let Err(e) = TokenStore::save("microsoft", &account, &new_token) {
+        eprintln!("[OAuth] Failed to persist refreshed tokens to keyring: {}", e);
+    }
+
     Ok(new_token)
 }
 
+/// Begin an OAuth 2.0 Device Authorization Grant for headless logins
+#[tauri::command]
+async fn start_device_oauth(
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+) -> Result<DeviceFlow, String> {
+    let msal_client = MsalClient::new(client_id, client_secret, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    msal_client
+        .begin_device_flow()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Poll the device-flow token endpoint until the user approves or it expires
+#[tauri::command]
+async fn poll_device_oauth(
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    device_code: String,
+    interval: u64,
+    expires_in: u64,
+) -> Result<TokenData, String> {
+    let msal_client = MsalClient::new(client_id, client_secret, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    msal_client
+        .poll_device_token(device_code, interval, expires_in)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Revoke tokens on sign-out and clear local state
+///
+/// Invalidates the account's sessions server-side and deletes the stored
+/// entry from the OS keyring so disconnecting an account is complete.
+#[tauri::command]
+async fn revoke_oauth(
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    account: String,
+    token_data: TokenData,
+) -> Result<(), String> {
+    let msal_client = MsalClient::new(client_id, client_secret, redirect_uri)
+        .map_err(|e| e.to_string())?;
+
+    msal_client
+        .revoke(&token_data)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    TokenStore::delete("microsoft", &account).map_err(|e| e.to_string())
+}
+
 /// Check if token is expired
 #[tauri::command]
 fn check_token_expiration(token_data: TokenData) -> Result<bool, String> {
     Ok(is_token_expired(&token_data))
 }
 
+/// Persist tokens for an account in the OS keyring
+#[tauri::command]
+fn save_tokens(account: String, token_data: TokenData) -> Result<(), String> {
+    TokenStore::save("microsoft", &account, &token_data).map_err(|e| e.to_string())
+}
+
+/// Load stored tokens for an account from the OS keyring
+#[tauri::command]
+fn load_tokens(account: String) -> Result<Option<TokenData>, String> {
+    TokenStore::load("microsoft", &account).map_err(|e| e.to_string())
+}
+
+/// Delete any stored tokens for an account from the OS keyring
+#[tauri::command]
+fn delete_tokens(account: String) -> Result<(), String> {
+    TokenStore::delete("microsoft", &account).map_err(|e| e.to_string())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .manage(AppState {
             pkce_verifier: Mutex::new(None),
+            nonce: Mutex::new(None),
+            refresh_manager: RefreshManager::default(),
         })
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_store::Builder::default().build())
@@ -104,7 +217,13 @@ pub fn run() {
             start_oauth,
             complete_oauth,
             refresh_access_token,
-            check_token_expiration
+            check_token_expiration,
+            start_device_oauth,
+            poll_device_oauth,
+            revoke_oauth,
+            save_tokens,
+            load_tokens,
+            delete_tokens
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
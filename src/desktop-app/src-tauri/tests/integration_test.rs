@@ -29,7 +29,7 @@ mod integration_tests {
 
         assert!(auth_url_result.is_ok(), "Failed to generate authorization URL");
 
-        let (auth_url, _pkce_verifier, _csrf_token) = auth_url_result.unwrap();
+        let (auth_url, _pkce_verifier, _csrf_token, _nonce) = auth_url_result.unwrap();
 
         // Verify the URL contains all required parameters
         assert!(auth_url.contains("login.microsoftonline.com"));
@@ -53,6 +53,7 @@ mod integration_tests {
             refresh_token: "mock_refresh_token_67890".to_string(),
             expires_at: Utc::now() + Duration::days(90),
             scope: "offline_access Mail.Read User.Read".to_string(),
+            identity: None,
         };
 
         // 2. Token should be valid initially
@@ -76,6 +77,7 @@ mod integration_tests {
             refresh_token: "refresh_token".to_string(),
             expires_at: Utc::now() + Duration::minutes(3), // Within 5-minute buffer
             scope: "test".to_string(),
+            identity: None,
         };
 
         assert!(is_token_expired(&almost_expired), "Token within buffer should be considered expired");
@@ -92,6 +94,7 @@ mod integration_tests {
             refresh_token: "test".to_string(),
             expires_at: Utc::now() + Duration::days(90),
             scope: "test".to_string(),
+            identity: None,
         };
 
         // Should not be expired
@@ -148,6 +151,7 @@ mod integration_tests {
             refresh_token: "test".to_string(),
             expires_at: Utc::now() + Duration::minutes(5),
             scope: "test".to_string(),
+            identity: None,
         };
         // Should be considered expired (buffer is inclusive)
         assert!(is_token_expired(&token_5min), "Token at 5-minute boundary should be expired");
@@ -158,6 +162,7 @@ mod integration_tests {
             refresh_token: "test".to_string(),
             expires_at: Utc::now() + Duration::minutes(6),
             scope: "test".to_string(),
+            identity: None,
         };
         assert!(!is_token_expired(&token_6min), "Token beyond buffer should be valid");
 
@@ -167,6 +172,7 @@ mod integration_tests {
             refresh_token: "test".to_string(),
             expires_at: Utc::now() - Duration::hours(1),
             scope: "test".to_string(),
+            identity: None,
         };
         assert!(is_token_expired(&token_past), "Past token should be expired");
 